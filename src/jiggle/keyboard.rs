@@ -0,0 +1,25 @@
+use usbd_hid::descriptor::KeyboardReport;
+
+/// HID usage ID for F13.
+/// Chosen because it has no default binding on Windows/macOS/Linux, so tapping
+/// it nudges keyboard-activity-based idle detectors without side effects.
+pub const TAP_KEY: u8 = 0x68;
+
+/// Build the "key down" and "key up" reports for a single harmless keypress.
+/// The caller is expected to send the down report, then the up report shortly
+/// after to release the key.
+pub fn tap_reports() -> (KeyboardReport, KeyboardReport) {
+    let down = KeyboardReport {
+        modifier: 0,
+        reserved: 0,
+        leds: 0,
+        keycodes: [TAP_KEY, 0, 0, 0, 0, 0],
+    };
+    let up = KeyboardReport {
+        modifier: 0,
+        reserved: 0,
+        leds: 0,
+        keycodes: [0, 0, 0, 0, 0, 0],
+    };
+    (down, up)
+}
@@ -0,0 +1,3 @@
+pub mod keyboard;
+pub mod movement;
+pub mod state;
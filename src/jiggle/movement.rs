@@ -1,9 +1,9 @@
 use heapless;
 
+#[derive(Clone, Copy)]
 pub struct Movement {
     upper_limit: u8,
     lower_limit: u8,
-    step: i8,
 }
 
 impl Movement {
@@ -11,47 +11,91 @@ impl Movement {
         Self {
             upper_limit: 32,
             lower_limit: 6,
-            step: 6,
         }
     }
 
-    /// Generate a relative movement vector suitable for use in a mouse HID report
-    /// The generated vector is a for a single axis, and returns to the starting position
-    pub fn generate_vector<const N: usize>(&self, seed: u32, vec: &mut heapless::Vec<i8, N>) {
-        // Scale rng_value into the range [LOWER, UPPER] inclusive.
-        // Use 64-bit intermediate to avoid overflow and get decent distribution.
+    /// Build a `Movement` from host-supplied limits (see the feature-report
+    /// config in `jiggle::state`).
+    pub const fn with_limits(upper_limit: u8, lower_limit: u8) -> Self {
+        Self {
+            upper_limit,
+            lower_limit,
+        }
+    }
+
+    pub fn upper_limit(&self) -> u8 {
+        self.upper_limit
+    }
+
+    pub fn lower_limit(&self) -> u8 {
+        self.lower_limit
+    }
+
+    /// Scale a raw RNG draw into the range `[lower_limit, upper_limit]` inclusive.
+    /// Uses a 64-bit intermediate to avoid overflow and get a decent distribution.
+    fn scale_seed(&self, seed: u32) -> u8 {
         let range: u32 = (self.upper_limit - self.lower_limit) as u32;
         let scaled: u32 = if seed == u32::MAX {
             range
         } else {
             ((seed as u64 * range as u64) / (u32::MAX as u64)) as u32
         };
-        let x_u8 = (self.lower_limit as u32 + scaled) as u8;
-        let mut remaining: i8 = x_u8 as i8;
-
-        // Populate forward movement in STEP-sized chunks (last chunk may be smaller).
-        while remaining > 0 && !vec.is_full() {
-            let to_push: i8 = if remaining >= self.step {
-                self.step
-            } else {
-                remaining
-            };
-            if vec.push(to_push).is_err() {
+        (self.lower_limit as u32 + scaled) as u8
+    }
+
+    /// Generate a two-axis movement path that traces a smooth closed loop back
+    /// to the origin, rather than a single-axis twitch. `(dx, dy)` deltas ramp
+    /// up and back down following the smoothstep curve `P(f) = 3f^2 - 2f^3`
+    /// (whose derivative `6f(1-f)` is a quadratic "bump" velocity profile), so
+    /// the cursor eases in and out of the move instead of stepping at a
+    /// constant rate.
+    pub fn generate_path<const N: usize>(
+        &self,
+        seed_x: u32,
+        seed_y: u32,
+        vec: &mut heapless::Vec<(i8, i8), N>,
+    ) {
+        let target_x = self.scale_seed(seed_x) as i32;
+        let target_y = self.scale_seed(seed_y) as i32;
+
+        // Half the reports trace out to the target, half trace back - the
+        // return half mirrors the outward half (see below).
+        let steps = (N / 2) as i32;
+        if steps == 0 {
+            return;
+        }
+
+        // Cumulative eased position at step `i` of `steps`. Telescoping the
+        // per-step differences of this (i.e. cumulative(i) - cumulative(i-1))
+        // always sums to cumulative(steps) - cumulative(0) = target, exactly,
+        // regardless of the rounding of any individual step.
+        let cumulative = |i: i32, target: i32| -> i32 {
+            (target * (3 * i * i * steps - 2 * i * i * i)) / (steps * steps * steps)
+        };
+
+        let mut prev_x = 0;
+        let mut prev_y = 0;
+        for i in 1..=steps {
+            let cum_x = cumulative(i, target_x);
+            let cum_y = cumulative(i, target_y);
+            let dx = (cum_x - prev_x).clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+            let dy = (cum_y - prev_y).clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+            prev_x = cum_x;
+            prev_y = cum_y;
+            if vec.push((dx, dy)).is_err() {
                 break;
             }
-            remaining -= to_push;
         }
 
-        // Mirror back to origin. Iterate in reverse over current values and push negated values
-        // until the vector is full.
-        // Note: negating a value in the expected range (1..=16) is safe for i8.
+        // Mirror back to the origin: replay the forward deltas in reverse,
+        // negated. Safe because the forward deltas are always >= 0 (targets
+        // are non-negative), so the negation never overflows i8.
         let clone = vec.clone();
-        for &v in clone.iter().rev() {
+        for &(dx, dy) in clone.iter().rev() {
             if vec.is_full() {
                 break;
             }
-            // push negated value; ignore push failure because we checked is_full above
-            let _ = vec.push(-v);
+            let _ = vec.push((-dx, -dy));
         }
     }
 }
@@ -1,41 +1,199 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::mutex::Mutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
 
-pub struct State {
-    // Jiggle On/Off switch
-    // This is wrapped in a mutex for convenience of sharing between tasks/coroutines
-    mutex: Mutex<CriticalSectionRawMutex, bool>,
+use super::movement::Movement;
+
+/// Which HID interface(s) the jiggle loops should drive.
+/// Cycled through by repeated BOOT button presses; `Off` preserves the old
+/// enable/disable behaviour for people who just want the device quiet.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum JiggleMode {
+    MouseOnly,
+    KeyboardOnly,
+    Both,
+    Off,
 }
 
-impl State {
+impl JiggleMode {
+    /// Advance to the next mode in the BOOT-button cycle
+    fn next(self) -> Self {
+        match self {
+            JiggleMode::MouseOnly => JiggleMode::KeyboardOnly,
+            JiggleMode::KeyboardOnly => JiggleMode::Both,
+            JiggleMode::Both => JiggleMode::Off,
+            JiggleMode::Off => JiggleMode::MouseOnly,
+        }
+    }
+
+    /// Whether this mode should be sending mouse reports
+    pub fn jiggles_mouse(self) -> bool {
+        matches!(self, JiggleMode::MouseOnly | JiggleMode::Both)
+    }
+
+    /// Whether this mode should be sending keyboard reports
+    pub fn jiggles_keyboard(self) -> bool {
+        matches!(self, JiggleMode::KeyboardOnly | JiggleMode::Both)
+    }
+
+    fn from_byte(value: u8) -> Self {
+        match value {
+            1 => JiggleMode::MouseOnly,
+            2 => JiggleMode::KeyboardOnly,
+            3 => JiggleMode::Both,
+            _ => JiggleMode::Off,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            JiggleMode::Off => 0,
+            JiggleMode::MouseOnly => 1,
+            JiggleMode::KeyboardOnly => 2,
+            JiggleMode::Both => 3,
+        }
+    }
+}
+
+/// Host-configurable jiggle settings, round-tripped through a vendor HID
+/// feature report so a desktop tool can tune the device without reflashing.
+///
+/// Report layout (5 bytes): mode byte, idle interval in seconds (u16 LE, 2
+/// bytes), movement upper_limit, movement lower_limit.
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub mode: JiggleMode,
+    pub idle_interval_secs: u16,
+    pub movement: Movement,
+}
+
+impl Config {
+    pub const REPORT_SIZE: usize = 5;
+
+    /// Lowest idle interval a host is allowed to configure. Zero would make
+    /// `in_fut` and the suspended branch of `usb_fut` spin their `Timer::after`
+    /// calls with no delay at all, flooding mouse reports and remote-wakeup
+    /// requests in a tight loop.
+    const MIN_IDLE_INTERVAL_SECS: u16 = 1;
+
     pub const fn new() -> Self {
+        // A second shy of 5 mins before the next wiggle by default - 5 mins
+        // being a typical timeout for screen savers and sleep modes. Two
+        // seconds in debug mode, so testing doesn't mean waiting around.
+        let idle_interval_secs = if cfg!(debug_assertions) { 2 } else { 60 * 5 - 1 };
         Self {
-            mutex: Mutex::new(true),
+            mode: JiggleMode::MouseOnly,
+            idle_interval_secs,
+            movement: Movement::new(),
+        }
+    }
+
+    /// Parse a feature report written by the host. Rejects limits that would
+    /// make `Movement` misbehave (`lower_limit` above `upper_limit`), and
+    /// idle intervals too short to be anything but a busy-loop.
+    pub fn from_report(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::REPORT_SIZE {
+            return None;
+        }
+        let upper_limit = data[3];
+        let lower_limit = data[4];
+        let idle_interval_secs = u16::from_le_bytes([data[1], data[2]]);
+        if lower_limit > upper_limit || idle_interval_secs < Self::MIN_IDLE_INTERVAL_SECS {
+            return None;
         }
+        Some(Self {
+            mode: JiggleMode::from_byte(data[0]),
+            idle_interval_secs,
+            movement: Movement::with_limits(upper_limit, lower_limit),
+        })
     }
 
-    /// Return the jiggle state
-    /// This waits on the jiggle state mutex
-    pub async fn is_enabled(&self) -> bool {
-        let state: bool;
-        {
-            let unlocked = self.mutex.lock().await;
-            state = *unlocked;
-            // Implicit release mutex at end of inner scope
+    /// Serialize in the same layout `from_report` parses, for `get_report` to
+    /// echo the current config back to the host. Returns the number of bytes
+    /// written, or 0 if `buf` is too small.
+    pub fn to_report(self, buf: &mut [u8]) -> usize {
+        if buf.len() < Self::REPORT_SIZE {
+            return 0;
         }
-        state
+        buf[0] = self.mode.to_byte();
+        let idle = self.idle_interval_secs.to_le_bytes();
+        buf[1] = idle[0];
+        buf[2] = idle[1];
+        buf[3] = self.movement.upper_limit();
+        buf[4] = self.movement.lower_limit();
+        Self::REPORT_SIZE
     }
+}
+
+pub struct State {
+    // Host-configurable settings, accessed from both async tasks and the
+    // synchronous `RequestHandler` callbacks, so a blocking critical-section
+    // mutex is used instead of the async `Mutex` - there's never any reason
+    // to wait for it.
+    config: Mutex<CriticalSectionRawMutex, RefCell<Config>>,
+    // Whether the USB bus is currently suspended by the host.
+    // Updated from the device handler, read from the mouse and keyboard jiggle
+    // loops, so a plain atomic is enough - no need to hold up a task waiting
+    // on the mutex.
+    suspended: AtomicBool,
+}
 
-    /// Toggle the jiggle state, and return the new state
-    /// This waits on the jiggle state mutex
-    pub async fn toggle(&self) -> bool {
-        let state: bool;
-        {
-            let mut unlocked = self.mutex.lock().await;
-            *unlocked = !(*unlocked);
-            state = *unlocked;
-            // Implicit release mutex at end of inner scope
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            config: Mutex::new(RefCell::new(Config::new())),
+            suspended: AtomicBool::new(false),
         }
-        state
+    }
+
+    /// Return a copy of the current config
+    pub fn config(&self) -> Config {
+        self.config.lock(|c| *c.borrow())
+    }
+
+    /// Replace the current config, e.g. from a host feature report
+    pub fn set_config(&self, config: Config) {
+        self.config.lock(|c| *c.borrow_mut() = config);
+    }
+
+    /// Return the current jiggle mode
+    pub fn mode(&self) -> JiggleMode {
+        self.config().mode
+    }
+
+    /// Return whether any interface is currently being jiggled
+    pub fn is_enabled(&self) -> bool {
+        self.mode() != JiggleMode::Off
+    }
+
+    /// Cycle to the next jiggle mode, and return it
+    pub fn cycle_mode(&self) -> JiggleMode {
+        self.config.lock(|c| {
+            let mut config = c.borrow_mut();
+            config.mode = config.mode.next();
+            config.mode
+        })
+    }
+
+    /// Return the configured delay between jiggles
+    pub fn idle_interval(&self) -> Duration {
+        Duration::from_secs(self.config().idle_interval_secs as u64)
+    }
+
+    /// Return the configured movement generator
+    pub fn movement(&self) -> Movement {
+        self.config().movement
+    }
+
+    /// Record whether the USB bus is currently suspended
+    pub fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+    }
+
+    /// Return whether the USB bus is currently suspended
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::Relaxed)
     }
 }
@@ -5,16 +5,18 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::{info, warn};
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
 use embassy_rp::bind_interrupts;
 use embassy_rp::clocks::RoscRng;
 use embassy_rp::gpio::{Input, Level, Output, Pull};
 use embassy_rp::peripherals::USB;
 use embassy_rp::usb::{Driver, InterruptHandler};
-use embassy_time::{Duration, Timer};
+use embassy_time::Timer;
 use embassy_usb::class::hid::{HidReaderWriter, ReportId, RequestHandler, State};
 use embassy_usb::control::OutResponse;
+use embassy_usb::msos::{self, windows_version};
 use embassy_usb::{Builder, Config, Handler};
-use usbd_hid::descriptor::{MouseReport, SerializedDescriptor};
+use usbd_hid::descriptor::{KeyboardReport, MouseReport, SerializedDescriptor};
 
 use {defmt_rtt as _, panic_probe as _};
 
@@ -22,6 +24,30 @@ mod jiggle;
 
 static JIGGLE_STATE: jiggle::state::State = jiggle::state::State::new();
 
+/// Interface GUID advertised to Windows via the MSOS 2.0 descriptor, so the
+/// desktop config tool can look us up without an INF.
+#[cfg(feature = "msos-winusb")]
+const WINUSB_DEVICE_INTERFACE_GUID: &str = "{6F4B4C1F-2E0F-4B8E-9C5A-6C7CF4B3F0B1}";
+
+/// A second top-level collection, appended after `MouseReport::desc()` on the
+/// mouse interface, declaring the vendor Feature report that carries
+/// `jiggle::state::Config`. Unnumbered (no Report ID item), matching
+/// `MouseReport`'s own report - mixing an ID'd report with an un-ID'd one in
+/// the same descriptor would make hosts misparse the boot mouse report as
+/// having an ID-prefix byte.
+const JIGGLE_FEATURE_REPORT_DESC: [u8; 21] = [
+    0x06, 0x00, 0xFF, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, // Usage (0x01)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x02, //   Usage (0x02)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, jiggle::state::Config::REPORT_SIZE as u8, //   Report Count
+    0xB1, 0x02, //   Feature (Data,Var,Abs)
+    0xC0, // End Collection
+];
+
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
 });
@@ -40,18 +66,24 @@ async fn main(_spawner: Spawner) {
     config.serial_number = Some("SN-01-0000842");
     config.max_power = 100;
     config.max_packet_size_0 = 64;
+    // Let the host suspend the bus without the device being disconnected, and
+    // allow us to signal it back awake instead of uselessly retrying writes.
+    config.supports_remote_wakeup = true;
 
     // Create embassy-usb DeviceBuilder using the driver and config.
     // It needs some buffers for building the descriptors.
     let mut config_descriptor = [0; 256];
     let mut bos_descriptor = [0; 256];
-    // You can also add a Microsoft OS descriptor.
+    // Buffer for the Microsoft OS 2.0 descriptor (see the `msos-winusb` feature below).
     let mut msos_descriptor = [0; 256];
     let mut control_buf = [0; 64];
     let mut request_handler = MyRequestHandler {};
+    let mut mouse_out_request_handler = NoopRequestHandler {};
+    let mut kb_request_handler = KeyboardRequestHandler {};
     let mut device_handler = MyDeviceHandler::new();
 
     let mut state = State::new();
+    let mut kb_state = State::new();
 
     let mut builder = Builder::new(
         driver,
@@ -64,41 +96,115 @@ async fn main(_spawner: Spawner) {
 
     builder.handler(&mut device_handler);
 
+    // Declare the Microsoft OS 2.0 descriptor capability up front (this part
+    // is genuinely device-scoped - it's the header the host reads before it
+    // knows about any function). Gated behind a feature so non-Windows users
+    // who don't need it can opt out.
+    #[cfg(feature = "msos-winusb")]
+    builder.msos_descriptor(windows_version::WIN8_1, 0);
+
+    // The mouse report descriptor, plus a second top-level collection for the
+    // vendor config feature report, so a compliant HID parser can discover
+    // and size it instead of the host needing to know its shape out of band.
+    let mut mouse_report_descriptor: heapless::Vec<u8, 256> = heapless::Vec::new();
+    mouse_report_descriptor.extend_from_slice(MouseReport::desc()).unwrap();
+    mouse_report_descriptor
+        .extend_from_slice(&JIGGLE_FEATURE_REPORT_DESC)
+        .unwrap();
+
     // Create classes on the builder.
+    // Feature report Get/Set_Report requests arrive on the control pipe and
+    // are dispatched by the HID class's own control handler, which reads
+    // `request_handler` here - not by `reader.run()` below, which only
+    // services the interrupt OUT endpoint (and this interface declares no
+    // Output report for it to service).
     let config = embassy_usb::class::hid::Config {
-        report_descriptor: MouseReport::desc(),
-        request_handler: None,
+        report_descriptor: &mouse_report_descriptor,
+        request_handler: Some(&mut request_handler),
         poll_ms: 60,
         max_packet_size: 64,
     };
     let hid = HidReaderWriter::<_, 1, 8>::new(&mut builder, &mut state, config);
 
+    // Advertise a stable interface GUID over the MSOS 2.0 descriptor, so the
+    // config tool can look us up on Windows instead of needing an INF.
+    //
+    // MSOS 2.0 scopes a feature to a particular interface via a function
+    // subset header (keyed off bFirstInterface), not by where in the
+    // builder call sequence it's registered - calling this after the mouse
+    // HID function above does NOT attach it to that interface specifically.
+    // embassy-usb's HidReaderWriter::new() doesn't hand back the function
+    // builder it creates internally, so there's no way to reach a
+    // function-scoped registration through that helper; this remains a
+    // device-level property, which still lets the tool locate the device as
+    // a whole. Deliberately NOT paired with a "WINUSB" compatible ID: that
+    // would rebind the whole device to winusb.sys instead of the HID class
+    // driver, breaking the mouse/keyboard the device exists to provide, so
+    // the GUID here is informational only (a way to find the device), not a
+    // WinUSB driver binding - moot anyway until a desktop tool actually
+    // drives the feature report path above.
+    #[cfg(feature = "msos-winusb")]
+    builder.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+        "DeviceInterfaceGUIDs",
+        msos::PropertyData::RegMultiSz(&[WINUSB_DEVICE_INTERFACE_GUID]),
+    ));
+
+    // Second HID interface: a keyboard, so modes that tap a key still work on
+    // idle detectors that only look at keyboard activity.
+    let kb_config = embassy_usb::class::hid::Config {
+        report_descriptor: KeyboardReport::desc(),
+        request_handler: None,
+        poll_ms: 60,
+        max_packet_size: 64,
+    };
+    let kb_hid = HidReaderWriter::<_, 1, 8>::new(&mut builder, &mut kb_state, kb_config);
+
     // Build the builder.
     let mut usb = builder.build();
 
-    // Run the USB device.
-    let usb_fut = usb.run();
+    // Run the USB device, but drop back out whenever the bus suspends so we
+    // can decide whether to just wait for the host, or actively nudge it
+    // awake with a remote wakeup request.
+    let usb_fut = async {
+        loop {
+            usb.run_until_suspend().await;
+
+            if !JIGGLE_STATE.is_enabled() {
+                usb.wait_resume().await;
+                continue;
+            }
+
+            // Jiggle is enabled but the bus went to sleep under us: race the
+            // host resuming on its own against our own jiggle schedule, and if
+            // ours fires first, ask the host to wake up instead of letting the
+            // mouse report writes silently fail into a suspended bus.
+            match select(usb.wait_resume(), Timer::after(JIGGLE_STATE.idle_interval())).await {
+                Either::First(_) => {}
+                Either::Second(_) => match usb.remote_wakeup().await {
+                    Ok(()) => info!("Sent USB remote wakeup"),
+                    Err(e) => warn!("Remote wakeup not supported by host: {:?}", e),
+                },
+            }
+        }
+    };
 
     let (reader, mut writer) = hid.split();
+    let (kb_reader, mut kb_writer) = kb_hid.split();
 
     let in_fut = async {
         let mut rng = RoscRng;
 
-        // Jiggle delay
-        let duration;
-        if cfg!(debug_assertions) {
-            // Two seconds in debug mode
-            duration = Duration::from_secs(2);
-        } else {
-            // a second shy of 5 mins before the next wiggle.
-            // 5 mins being a typical timeout for screen savers and sleep modes.
-            duration = Duration::from_secs(60 * 5 - 1);
-        }
-
         loop {
-            // Should we jiggle?
-            if !JIGGLE_STATE.is_enabled().await {
-                // Jiggle is disabled, wait a bit and check again in next iteration
+            // Should we jiggle the mouse?
+            if !JIGGLE_STATE.mode().jiggles_mouse() {
+                // Mouse jiggle is disabled, wait a bit and check again in next iteration
+                _ = Timer::after_millis(1000).await;
+                continue;
+            }
+
+            if JIGGLE_STATE.is_suspended() {
+                // Bus is suspended; usb_fut is responsible for waking the host
+                // back up, so just wait instead of spamming dead writes.
                 _ = Timer::after_millis(1000).await;
                 continue;
             }
@@ -107,21 +213,18 @@ async fn main(_spawner: Spawner) {
 
             // To simulate more natural mouse movement, limit the maximum movement per report, and send multiple reports.
             const JIGGLE_VECTOR_SIZE: usize = 32;
-            let mut jiggle_vector: heapless::Vec<i8, JIGGLE_VECTOR_SIZE> = heapless::Vec::new();
-            let reverberations = 2;
-            let movement = jiggle::movement::Movement::new();
-            for _ in 0..reverberations {
-                movement.generate_vector(rng.next_u32(), &mut jiggle_vector);
-            }
+            let mut jiggle_vector: heapless::Vec<(i8, i8), JIGGLE_VECTOR_SIZE> = heapless::Vec::new();
+            let movement = JIGGLE_STATE.movement();
+            movement.generate_path(rng.next_u32(), rng.next_u32(), &mut jiggle_vector);
 
             // See https://wiki.osdev.org/USB_Human_Interface_Devices#USB_mouse for details on mouse reports.
             // tldr: x and y are signed 8-bit integers representing relative movement.
-            for x in jiggle_vector {
+            for (x, y) in jiggle_vector {
                 // Create the mouse HID report.
                 let report = MouseReport {
                     buttons: 0,
-                    x: x,
-                    y: 0,
+                    x,
+                    y,
                     wheel: 0,
                     pan: 0,
                 };
@@ -134,55 +237,185 @@ async fn main(_spawner: Spawner) {
             }
 
             // Wait a before next jiggle
-            _ = Timer::after(duration).await;
+            _ = Timer::after(JIGGLE_STATE.idle_interval()).await;
+        }
+    };
+
+    let kb_fut = async {
+        loop {
+            // Should we jiggle the keyboard?
+            if !JIGGLE_STATE.mode().jiggles_keyboard() {
+                // Keyboard jiggle is disabled, wait a bit and check again in next iteration
+                _ = Timer::after_millis(1000).await;
+                continue;
+            }
+
+            if JIGGLE_STATE.is_suspended() {
+                // Bus is suspended; usb_fut is responsible for waking the host
+                // back up, so just wait instead of spamming dead writes.
+                _ = Timer::after_millis(1000).await;
+                continue;
+            }
+
+            // Tap a harmless key and release it, to nudge idle detectors that
+            // only key off of keyboard activity.
+            let (down, up) = jiggle::keyboard::tap_reports();
+            match kb_writer.write_serialize(&down).await {
+                Ok(()) => {}
+                Err(e) => warn!("Failed to send key down report: {:?}", e),
+            }
+            _ = Timer::after_millis(50).await;
+            match kb_writer.write_serialize(&up).await {
+                Ok(()) => {}
+                Err(e) => warn!("Failed to send key up report: {:?}", e),
+            }
+
+            // Wait a before next jiggle
+            _ = Timer::after(JIGGLE_STATE.idle_interval()).await;
         }
     };
 
-    let out_fut = async {
-        reader.run(false, &mut request_handler).await;
+    let mouse_out_fut = async {
+        reader.run(false, &mut mouse_out_request_handler).await;
+    };
+
+    let kb_out_fut = async {
+        kb_reader.run(false, &mut kb_request_handler).await;
     };
 
     let led_fut = async {
         let mut button = Input::new(p.PIN_23, Pull::Down);
         let mut led_g: Output<'_> = Output::new(p.PIN_19, Level::Low);
-        // Only the green LED is used, however the device powers on with both red and blue on
-        // Initialise and turn off red and blue LEDs
-        let _led_r: Output<'_> = Output::new(p.PIN_18, Level::High);
+        let mut led_r: Output<'_> = Output::new(p.PIN_18, Level::High);
+        // Blue is left unused - off for every mode.
         let _led_b: Output<'_> = Output::new(p.PIN_20, Level::High);
 
         loop {
             // Blocking wait for BOOT button press
             button.wait_for_falling_edge().await;
 
-            // Toggle and get state
-            let state = JIGGLE_STATE.toggle().await;
+            // Cycle and get the new mode
+            let mode = JIGGLE_STATE.cycle_mode();
 
-            // Update LED color based on state
-            if state {
-                // Jiggle enabled: green
-                led_g.set_low();
-            } else {
-                // Jiggle disabled: off
-                led_g.set_high();
+            // Update LED colors based on mode: green for mouse, red for
+            // keyboard, both together for Both, off for Off.
+            match mode {
+                jiggle::state::JiggleMode::MouseOnly => {
+                    led_g.set_low();
+                    led_r.set_high();
+                }
+                jiggle::state::JiggleMode::KeyboardOnly => {
+                    led_g.set_high();
+                    led_r.set_low();
+                }
+                jiggle::state::JiggleMode::Both => {
+                    led_g.set_low();
+                    led_r.set_low();
+                }
+                jiggle::state::JiggleMode::Off => {
+                    led_g.set_high();
+                    led_r.set_high();
+                }
             }
         }
     };
 
     // Run everything concurrently.
     // If we had made everything `'static` above instead, we could do this using separate tasks instead.
-    join(usb_fut, join(in_fut, join(out_fut, led_fut))).await;
+    join(
+        usb_fut,
+        join(
+            in_fut,
+            join(kb_fut, join(mouse_out_fut, join(kb_out_fut, led_fut))),
+        ),
+    )
+    .await;
 }
 
+/// Control-pipe handler for the mouse interface's vendor config feature
+/// report (see `JIGGLE_FEATURE_REPORT_DESC`), wired up via
+/// `hid::Config.request_handler` rather than `reader.run()` - Feature
+/// Get/Set_Report requests are control transfers, not interrupt OUT data.
+/// Only answers Get/Set_Report for that Feature report; anything else on
+/// this interface is left alone rather than being misparsed as a config blob.
 struct MyRequestHandler {}
 
 impl RequestHandler for MyRequestHandler {
+    fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        if !matches!(id, ReportId::Feature(_)) {
+            return None;
+        }
+        info!("Get report for {:?}", id);
+        match JIGGLE_STATE.config().to_report(buf) {
+            0 => None,
+            len => Some(len),
+        }
+    }
+
+    fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
+        if !matches!(id, ReportId::Feature(_)) {
+            return OutResponse::Rejected;
+        }
+        info!("Set report for {:?}: {=[u8]}", id, data);
+        match jiggle::state::Config::from_report(data) {
+            Some(config) => {
+                JIGGLE_STATE.set_config(config);
+                OutResponse::Accepted
+            }
+            None => {
+                warn!("Rejected malformed jiggle config report");
+                OutResponse::Rejected
+            }
+        }
+    }
+
+    fn set_idle_ms(&mut self, id: Option<ReportId>, dur: u32) {
+        info!("Set idle rate for {:?} to {:?}", id, dur);
+    }
+
+    fn get_idle_ms(&mut self, id: Option<ReportId>) -> Option<u32> {
+        info!("Get idle rate for {:?}", id);
+        None
+    }
+}
+
+/// Handler passed to `reader.run()` for the mouse interface's interrupt OUT
+/// endpoint. The mouse report descriptor declares no Output report, so this
+/// never actually receives data; it exists only to satisfy `reader.run()`'s
+/// signature now that `MyRequestHandler` is handed to `Config.request_handler`
+/// instead (see the comment above the mouse `hid::Config`).
+struct NoopRequestHandler {}
+
+impl RequestHandler for NoopRequestHandler {
+    fn get_report(&mut self, _id: ReportId, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
+
+    fn set_report(&mut self, _id: ReportId, _data: &[u8]) -> OutResponse {
+        OutResponse::Accepted
+    }
+
+    fn set_idle_ms(&mut self, _id: Option<ReportId>, _dur: u32) {}
+
+    fn get_idle_ms(&mut self, _id: Option<ReportId>) -> Option<u32> {
+        None
+    }
+}
+
+/// Request handler for the keyboard interface. It carries no config feature
+/// report of its own, so Set_Report here is just the host writing LED state
+/// (num/caps/scroll lock) - accept it without trying to parse it as jiggle
+/// config.
+struct KeyboardRequestHandler {}
+
+impl RequestHandler for KeyboardRequestHandler {
     fn get_report(&mut self, id: ReportId, _buf: &mut [u8]) -> Option<usize> {
         info!("Get report for {:?}", id);
         None
     }
 
     fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
-        info!("Set report for {:?}: {=[u8]}", id, data);
+        info!("Set report (LED state) for {:?}: {=[u8]}", id, data);
         OutResponse::Accepted
     }
 
@@ -238,4 +471,13 @@ impl Handler for MyDeviceHandler {
             info!("Device is no longer configured, the Vbus current limit is 100mA.");
         }
     }
+
+    fn suspended(&mut self, suspended: bool) {
+        JIGGLE_STATE.set_suspended(suspended);
+        if suspended {
+            info!("Device suspended, the Vbus current limit is 500uA (or 2.5mA for high-power devices with remote wakeup enabled).");
+        } else {
+            info!("Device resumed, the Vbus current limit is back to the configured limit.");
+        }
+    }
 }